@@ -1,8 +1,10 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicUsize;
+use std::sync::RwLock as StdRwLock;
 
 use tokio::{
     io::{BufWriter, Stdout},
-    sync::RwLock,
+    sync::{oneshot, RwLock},
 };
 
 use glomers::{Body, Message, MsgHandler};
@@ -56,16 +58,18 @@ fn node_group(value: &String) -> u32 {
 
 struct BroadcastNode {
     id: String,
-    msg_id: usize,
+    msg_id: AtomicUsize,
     output: RwLock<BufWriter<Stdout>>,
     /// Data this node has seen
-    seen_data: HashSet<usize>,
+    seen_data: StdRwLock<HashSet<usize>>,
     /// Status for each peer/datum
-    peer_data: HashMap<String, HashMap<usize, DatumStatus>>,
+    peer_data: StdRwLock<HashMap<String, HashMap<usize, DatumStatus>>>,
+    /// Replies awaited by an in-flight `rpc` call
+    pending: RwLock<HashMap<usize, oneshot::Sender<Message<BroadcastMessages>>>>,
 }
 
 impl MsgHandler<BroadcastMessages> for BroadcastNode {
-    fn new(partial_node: glomers::PartialNode) -> Self
+    fn new(partial_node: glomers::PartialNode<BroadcastMessages>) -> Self
     where
         Self: MsgHandler<BroadcastMessages>,
     {
@@ -110,30 +114,27 @@ impl MsgHandler<BroadcastMessages> for BroadcastNode {
         }
         BroadcastNode {
             id: partial_node.id,
-            msg_id: partial_node.msg_id,
+            msg_id: AtomicUsize::new(partial_node.msg_id),
             output: partial_node.output,
-            seen_data: HashSet::new(),
-            peer_data,
+            seen_data: StdRwLock::new(HashSet::new()),
+            peer_data: StdRwLock::new(peer_data),
+            pending: partial_node.pending,
         }
     }
 
-    async fn handle_msg(&mut self, msg: Message<BroadcastMessages>)
+    async fn handle_msg(&self, msg: Message<BroadcastMessages>)
     where
         BroadcastMessages: Serialize,
     {
         match msg.body.msg {
             BroadcastMessages::Broadcast { message } => {
-                self.seen_data.insert(message);
+                self.seen_data.write().unwrap().insert(message);
                 self.reply(&msg, BroadcastMessages::BroadcastOk).await;
             }
             BroadcastMessages::Read => {
-                self.reply(
-                    &msg,
-                    BroadcastMessages::ReadOk {
-                        messages: self.seen_data.clone(),
-                    },
-                )
-                .await;
+                let messages = self.seen_data.read().unwrap().clone();
+                self.reply(&msg, BroadcastMessages::ReadOk { messages })
+                    .await;
             }
             BroadcastMessages::Topology {
                 topology: ref _topology,
@@ -163,26 +164,35 @@ impl MsgHandler<BroadcastMessages> for BroadcastNode {
                 data_you_need,
                 data_i_received_from_you,
             } => {
-                // We don't reply to a gossip, we gossip on a schedule. This code handles *receiving* a gossip message
-                let peer_data = self.peer_data.entry(msg.src).or_insert(HashMap::new());
-                let mut new_data = Vec::new();
-                for datum in data_you_need.difference(&self.seen_data) {
-                    // If we haven't seen this entry before, register that we've seen it
-                    let status = peer_data
-                        .entry(*datum)
-                        .or_insert(DatumStatus::ReceivedUnconfirmed);
-                    *status = DatumStatus::ReceivedUnconfirmed;
-                    new_data.push(*datum);
-                }
-                self.seen_data.extend(new_data);
-
-                // All 'data I received from you' values are now confirmed.
-                for datum in data_i_received_from_you {
-                    *peer_data
-                        .get_mut(&datum)
-                        .expect("We should already be tracking a datum for this node") =
-                        DatumStatus::Confirmed;
+                // We don't reply to a gossip, we gossip on a schedule. This code handles *receiving* a gossip message.
+                //
+                // `seen_data` and `peer_data` are always locked in that order (matching
+                // `bg_task`) and never held at the same time as each other here, so this
+                // can't deadlock against `bg_task` taking the same two locks.
+                let new_data: Vec<usize> = {
+                    let seen_data = self.seen_data.read().unwrap();
+                    data_you_need.difference(&seen_data).copied().collect()
+                };
+
+                {
+                    let mut peer_data = self.peer_data.write().unwrap();
+                    let peer_data = peer_data.entry(msg.src).or_insert_with(HashMap::new);
+
+                    // If we haven't seen these entries before, register that we've seen them
+                    for datum in &new_data {
+                        peer_data.insert(*datum, DatumStatus::ReceivedUnconfirmed);
+                    }
+
+                    // All 'data I received from you' values are now confirmed.
+                    for datum in data_i_received_from_you {
+                        *peer_data
+                            .get_mut(&datum)
+                            .expect("We should already be tracking a datum for this node") =
+                            DatumStatus::Confirmed;
+                    }
                 }
+
+                self.seen_data.write().unwrap().extend(new_data);
             }
             BroadcastMessages::BroadcastOk
             | BroadcastMessages::ReadOk { messages: _ }
@@ -190,36 +200,69 @@ impl MsgHandler<BroadcastMessages> for BroadcastNode {
         }
     }
 
-    fn bg_task_interval_ms(&self) -> u64 {
-        250
+    fn pacing_policy(&self) -> glomers::PacingPolicy {
+        // Gossip faster while many peer/datum entries are unconfirmed, and grow toward
+        // every half-second only while none are. `ReceivedUnconfirmed` entries are never
+        // moved to `Confirmed` on this side, so once this node has learned a datum by
+        // gossip the backlog stays above zero and the interval no longer grows.
+        glomers::PacingPolicy::Adaptive {
+            floor_ms: 25,
+            ceil_ms: 500,
+            target: 50,
+        }
+    }
+
+    fn bg_task_backlog(&self) -> usize {
+        self.peer_data
+            .read()
+            .unwrap()
+            .values()
+            .flat_map(|data_state| data_state.values())
+            .filter(|status| !matches!(status, DatumStatus::Confirmed))
+            .count()
     }
 
-    async fn bg_task(&mut self) {
-        for data_state in self.peer_data.values_mut() {
-            for datum in self.seen_data.iter() {
-                if !data_state.contains_key(&datum) {
-                    data_state.insert(*datum, DatumStatus::SentUnconfirmed);
+    async fn bg_task(&self) {
+        {
+            let seen_data = self.seen_data.read().unwrap();
+            let mut peer_data = self.peer_data.write().unwrap();
+            for data_state in peer_data.values_mut() {
+                for datum in seen_data.iter() {
+                    if !data_state.contains_key(datum) {
+                        data_state.insert(*datum, DatumStatus::SentUnconfirmed);
+                    }
                 }
             }
         }
 
-        for (count, (peer, data_state)) in self.peer_data.iter().enumerate() {
-            let data_you_need = data_state
-                .iter()
-                .filter(|(_d, s)| matches!(**s, DatumStatus::SentUnconfirmed))
-                .map(|(d, _s)| *d)
-                .collect::<HashSet<_>>();
-            let data_i_received_from_you = data_state
-                .iter()
-                .filter(|(_d, s)| matches!(s, DatumStatus::ReceivedUnconfirmed))
-                .map(|(d, _s)| *d)
-                .collect::<HashSet<_>>();
+        // Snapshot what to gossip to each peer up front so the locks are released
+        // before we start awaiting the (potentially slow) sends below.
+        let gossip: Vec<(String, HashSet<usize>, HashSet<usize>)> = self
+            .peer_data
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(peer, data_state)| {
+                let data_you_need = data_state
+                    .iter()
+                    .filter(|(_d, s)| matches!(**s, DatumStatus::SentUnconfirmed))
+                    .map(|(d, _s)| *d)
+                    .collect::<HashSet<_>>();
+                let data_i_received_from_you = data_state
+                    .iter()
+                    .filter(|(_d, s)| matches!(s, DatumStatus::ReceivedUnconfirmed))
+                    .map(|(d, _s)| *d)
+                    .collect::<HashSet<_>>();
+                (peer.clone(), data_you_need, data_i_received_from_you)
+            })
+            .collect();
 
+        for (peer, data_you_need, data_i_received_from_you) in gossip {
             let msg = Message {
                 src: self.id.clone(),
-                dst: peer.clone(),
+                dst: peer,
                 body: Body {
-                    msg_id: Some(self.msg_id.clone() + count),
+                    msg_id: Some(self.next_msg_id()),
                     in_reply_to: None,
                     msg: BroadcastMessages::Gossip {
                         data_you_need,
@@ -229,23 +272,31 @@ impl MsgHandler<BroadcastMessages> for BroadcastNode {
             };
             self.send_msg_inner(self.get_output(), msg).await;
         }
-        self.msg_id += self.peer_data.len();
     }
 
-    fn get_msg_id(&mut self) -> &mut usize {
-        &mut self.msg_id
+    fn get_msg_id(&self) -> &AtomicUsize {
+        &self.msg_id
     }
 
     fn get_output(&self) -> &RwLock<BufWriter<Stdout>> {
         &self.output
     }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_pending(
+        &self,
+    ) -> &RwLock<HashMap<usize, oneshot::Sender<Message<BroadcastMessages>>>> {
+        &self.pending
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let jh = tokio::spawn(BroadcastNode::run::<BroadcastMessages>());
     jh.await.unwrap();
-    panic!("Finished!!");
 }
 
 // echo '{"src":"c0","dest":"n3","body":{"type":"init","msg_id":1,"node_id":"n3","node_ids":["n1", "n2", "n3"]}}' | cargo run --bin broadcast
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+
 use tokio::{
     io::{BufWriter, Stdout},
-    sync::RwLock,
+    sync::{oneshot, RwLock},
 };
 
 use glomers::{Message, MsgHandler};
@@ -14,26 +17,28 @@ enum EchoMessages {
 }
 
 struct EchoNode {
-    _id: String,
+    id: String,
     _peers: Vec<String>,
-    msg_id: usize,
+    msg_id: AtomicUsize,
     output: RwLock<BufWriter<Stdout>>,
+    pending: RwLock<HashMap<usize, oneshot::Sender<Message<EchoMessages>>>>,
 }
 
 impl MsgHandler<EchoMessages> for EchoNode {
-    fn new(partial_node: glomers::PartialNode) -> Self
+    fn new(partial_node: glomers::PartialNode<EchoMessages>) -> Self
     where
         Self: MsgHandler<EchoMessages>,
     {
         EchoNode {
-            _id: partial_node.id,
+            id: partial_node.id,
             _peers: partial_node.node_ids,
-            msg_id: partial_node.msg_id,
+            msg_id: AtomicUsize::new(partial_node.msg_id),
             output: partial_node.output,
+            pending: partial_node.pending,
         }
     }
 
-    async fn handle_msg(&mut self, msg: Message<EchoMessages>)
+    async fn handle_msg(&self, msg: Message<EchoMessages>)
     where
         EchoMessages: Serialize,
     {
@@ -48,13 +53,21 @@ impl MsgHandler<EchoMessages> for EchoNode {
         }
     }
 
-    fn get_msg_id(&mut self) -> &mut usize {
-        &mut self.msg_id
+    fn get_msg_id(&self) -> &AtomicUsize {
+        &self.msg_id
     }
 
     fn get_output(&self) -> &RwLock<BufWriter<Stdout>> {
         &self.output
     }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_pending(&self) -> &RwLock<HashMap<usize, oneshot::Sender<Message<EchoMessages>>>> {
+        &self.pending
+    }
 }
 
 #[tokio::main]
@@ -1,8 +1,10 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use tokio::{
     io::{BufWriter, Stdout},
-    sync::RwLock,
+    sync::{oneshot, RwLock},
 };
 
 use glomers::{Message, MsgHandler};
@@ -18,24 +20,26 @@ enum GenerateMessages {
 struct UniqueIdNode {
     id: String,
     _peers: Vec<String>,
-    msg_id: usize,
+    msg_id: AtomicUsize,
     output: RwLock<BufWriter<Stdout>>,
+    pending: RwLock<HashMap<usize, oneshot::Sender<Message<GenerateMessages>>>>,
 }
 
 impl MsgHandler<GenerateMessages> for UniqueIdNode {
-    fn new(partial_node: glomers::PartialNode) -> Self
+    fn new(partial_node: glomers::PartialNode<GenerateMessages>) -> Self
     where
         Self: MsgHandler<GenerateMessages>,
     {
         UniqueIdNode {
             id: partial_node.id,
             _peers: partial_node.node_ids,
-            msg_id: partial_node.msg_id,
+            msg_id: AtomicUsize::new(partial_node.msg_id),
             output: partial_node.output,
+            pending: partial_node.pending,
         }
     }
 
-    async fn handle_msg(&mut self, msg: Message<GenerateMessages>)
+    async fn handle_msg(&self, msg: Message<GenerateMessages>)
     where
         GenerateMessages: Serialize,
     {
@@ -46,7 +50,7 @@ impl MsgHandler<GenerateMessages> for UniqueIdNode {
                     id: format!(
                         "{}-{}-{}",
                         self.id,
-                        self.msg_id,
+                        self.next_msg_id(),
                         SystemTime::now()
                             .duration_since(UNIX_EPOCH)
                             .unwrap()
@@ -58,13 +62,21 @@ impl MsgHandler<GenerateMessages> for UniqueIdNode {
         }
     }
 
-    fn get_msg_id(&mut self) -> &mut usize {
-        &mut self.msg_id
+    fn get_msg_id(&self) -> &AtomicUsize {
+        &self.msg_id
     }
 
     fn get_output(&self) -> &RwLock<BufWriter<Stdout>> {
         &self.output
     }
+
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_pending(&self) -> &RwLock<HashMap<usize, oneshot::Sender<Message<GenerateMessages>>>> {
+        &self.pending
+    }
 }
 
 #[tokio::main]
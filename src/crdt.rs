@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+/// Common interface for convergent replicated data types. Any two replicas that have
+/// observed the same set of updates, in any order and any number of times, end up
+/// with the same value once merged with each other: `merge` must be commutative,
+/// associative and idempotent.
+pub trait Crdt {
+    /// Fold `other`'s state into `self`.
+    fn merge(&mut self, other: &Self);
+}
+
+/// Grow-only counter. Each node tracks only its own contribution; the total is the
+/// sum across nodes and merge takes the element-wise max, so the value never shrinks.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GCounter {
+    counts: HashMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `amount` to `node_id`'s own contribution.
+    pub fn increment(&mut self, node_id: &str, amount: u64) {
+        *self.counts.entry(node_id.to_string()).or_insert(0) += amount;
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node_id, count) in &other.counts {
+            let entry = self.counts.entry(node_id.clone()).or_insert(0);
+            *entry = (*entry).max(*count);
+        }
+    }
+}
+
+/// Increment/decrement counter built from two `GCounter`s, one tracking increments
+/// (`p`) and one tracking decrements (`n`). Value is `sum(p) - sum(n)`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PNCounter {
+    p: GCounter,
+    n: GCounter,
+}
+
+impl PNCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&mut self, node_id: &str, amount: u64) {
+        self.p.increment(node_id, amount);
+    }
+
+    pub fn decrement(&mut self, node_id: &str, amount: u64) {
+        self.n.increment(node_id, amount);
+    }
+
+    pub fn value(&self) -> i64 {
+        self.p.value() as i64 - self.n.value() as i64
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.p.merge(&other.p);
+        self.n.merge(&other.n);
+    }
+}
+
+/// Last-writer-wins register. Merge keeps whichever side wrote with the higher
+/// `(timestamp, node_id)` pair, using `node_id` to break ties between equal timestamps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LwwRegister<T> {
+    value: T,
+    timestamp: u64,
+    node_id: String,
+}
+
+impl<T> LwwRegister<T> {
+    pub fn new(value: T, timestamp: u64, node_id: String) -> Self {
+        Self {
+            value,
+            timestamp,
+            node_id,
+        }
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Overwrite the register, but only if `(timestamp, node_id)` is at least as
+    /// recent as the current write.
+    pub fn set(&mut self, value: T, timestamp: u64, node_id: String) {
+        if (timestamp, &node_id) >= (self.timestamp, &self.node_id) {
+            self.value = value;
+            self.timestamp = timestamp;
+            self.node_id = node_id;
+        }
+    }
+}
+
+impl<T: Clone> Crdt for LwwRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if (other.timestamp, &other.node_id) > (self.timestamp, &self.node_id) {
+            self.value = other.value.clone();
+            self.timestamp = other.timestamp;
+            self.node_id = other.node_id.clone();
+        }
+    }
+}
+
+/// Observed-remove set. An element is present iff at least one of its add-tags
+/// hasn't been tombstoned, which gives concurrent-add-wins semantics over a remove.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrSet<T>
+where
+    T: Eq + Hash,
+{
+    adds: HashSet<(T, String)>,
+    tombstones: HashSet<String>,
+}
+
+impl<T> Default for OrSet<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        Self {
+            adds: HashSet::new(),
+            tombstones: HashSet::new(),
+        }
+    }
+}
+
+impl<T> OrSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `element`, tagged with a caller-supplied unique tag (e.g. `"{node_id}-{msg_id}"`).
+    pub fn add(&mut self, element: T, tag: String) {
+        self.adds.insert((element, tag));
+    }
+
+    /// Tombstone every tag currently observed for `element`.
+    pub fn remove(&mut self, element: &T) {
+        let tags: Vec<String> = self
+            .adds
+            .iter()
+            .filter(|(e, _)| e == element)
+            .map(|(_, tag)| tag.clone())
+            .collect();
+        self.tombstones.extend(tags);
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.adds
+            .iter()
+            .any(|(e, tag)| e == element && !self.tombstones.contains(tag))
+    }
+
+    pub fn elements(&self) -> HashSet<T> {
+        self.adds
+            .iter()
+            .filter(|(_, tag)| !self.tombstones.contains(tag))
+            .map(|(e, _)| e.clone())
+            .collect()
+    }
+}
+
+impl<T> Crdt for OrSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn merge(&mut self, other: &Self) {
+        self.adds.extend(other.adds.iter().cloned());
+        self.tombstones.extend(other.tombstones.iter().cloned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_merge_laws<C: Crdt + Clone + PartialEq + std::fmt::Debug>(a: &C, b: &C, c: &C) {
+        // Commutative: merge(a, b) == merge(b, a)
+        let mut ab = a.clone();
+        ab.merge(b);
+        let mut ba = b.clone();
+        ba.merge(a);
+        assert_eq!(ab, ba, "merge should be commutative");
+
+        // Associative: merge(merge(a, b), c) == merge(a, merge(b, c))
+        let mut ab_c = ab.clone();
+        ab_c.merge(c);
+        let mut bc = b.clone();
+        bc.merge(c);
+        let mut a_bc = a.clone();
+        a_bc.merge(&bc);
+        assert_eq!(ab_c, a_bc, "merge should be associative");
+
+        // Idempotent: merge(a, a) == a
+        let mut aa = a.clone();
+        aa.merge(a);
+        assert_eq!(&aa, a, "merge should be idempotent");
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct GCounterValue(GCounter);
+    impl Crdt for GCounterValue {
+        fn merge(&mut self, other: &Self) {
+            self.0.merge(&other.0);
+        }
+    }
+
+    #[test]
+    fn gcounter_merge_laws() {
+        let mut a = GCounter::new();
+        a.increment("n1", 3);
+        let mut b = GCounter::new();
+        b.increment("n2", 5);
+        let mut c = GCounter::new();
+        c.increment("n3", 1);
+        c.increment("n4", 2);
+
+        assert_merge_laws(&GCounterValue(a.clone()), &GCounterValue(b.clone()), &GCounterValue(c.clone()));
+
+        let mut merged = a;
+        merged.merge(&b);
+        merged.merge(&c);
+        assert_eq!(merged.value(), 3 + 5 + 1 + 2);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct PNCounterValue(PNCounter);
+    impl Crdt for PNCounterValue {
+        fn merge(&mut self, other: &Self) {
+            self.0.merge(&other.0);
+        }
+    }
+
+    #[test]
+    fn pncounter_merge_laws() {
+        let mut a = PNCounter::new();
+        a.increment("n1", 10);
+        let mut b = PNCounter::new();
+        b.decrement("n2", 4);
+        let mut c = PNCounter::new();
+        c.increment("n3", 1);
+
+        assert_merge_laws(&PNCounterValue(a.clone()), &PNCounterValue(b.clone()), &PNCounterValue(c.clone()));
+
+        let mut merged = a;
+        merged.merge(&b);
+        merged.merge(&c);
+        assert_eq!(merged.value(), 10 - 4 + 1);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct LwwRegisterValue(LwwRegister<&'static str>);
+    impl Crdt for LwwRegisterValue {
+        fn merge(&mut self, other: &Self) {
+            self.0.merge(&other.0);
+        }
+    }
+
+    #[test]
+    fn lww_register_merge_laws() {
+        let a = LwwRegister::new("a", 1, "n1".to_string());
+        let b = LwwRegister::new("b", 2, "n2".to_string());
+        let c = LwwRegister::new("c", 2, "n1".to_string());
+
+        assert_merge_laws(
+            &LwwRegisterValue(a.clone()),
+            &LwwRegisterValue(b.clone()),
+            &LwwRegisterValue(c.clone()),
+        );
+
+        // Tie-break: equal timestamps (b and c both write at 2) fall back to
+        // comparing node_id, and "n2" > "n1", so b's write wins.
+        let mut merged = b.clone();
+        merged.merge(&c);
+        assert_eq!(*merged.value(), "b");
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct OrSetValue(OrSet<&'static str>);
+    impl Crdt for OrSetValue {
+        fn merge(&mut self, other: &Self) {
+            self.0.merge(&other.0);
+        }
+    }
+
+    #[test]
+    fn orset_merge_laws_and_semantics() {
+        let mut a = OrSet::new();
+        a.add("x", "n1-1".to_string());
+        let mut b = OrSet::new();
+        b.add("y", "n2-1".to_string());
+        let mut c = OrSet::new();
+        c.remove(&"x");
+
+        assert_merge_laws(&OrSetValue(a.clone()), &OrSetValue(b.clone()), &OrSetValue(c.clone()));
+
+        // Concurrent add-wins: merging a remove that only tombstones one tag doesn't
+        // remove an element re-added under a different tag.
+        let mut concurrent_add = OrSet::new();
+        concurrent_add.add("x", "n1-1".to_string());
+        let mut concurrent_remove = concurrent_add.clone();
+        concurrent_remove.remove(&"x");
+        let mut re_add = concurrent_add.clone();
+        re_add.add("x", "n3-1".to_string());
+
+        let mut result = concurrent_remove;
+        result.merge(&re_add);
+        assert!(result.contains(&"x"));
+    }
+}
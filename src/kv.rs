@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{MsgHandler, RpcError};
+
+/// Maelstrom's standard error code for "the requested key does not exist".
+const ERROR_KEY_DOES_NOT_EXIST: u32 = 20;
+/// Maelstrom's standard error code for "the compare-and-swap precondition failed".
+const ERROR_PRECONDITION_FAILED: u32 = 22;
+
+/// Wire body for Maelstrom's `seq-kv`/`lin-kv`/`lww-kv` services.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KvBody<T> {
+    Read {
+        key: String,
+    },
+    ReadOk {
+        value: T,
+    },
+    Write {
+        key: String,
+        value: T,
+    },
+    WriteOk,
+    Cas {
+        key: String,
+        from: T,
+        to: T,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        create_if_not_exists: Option<bool>,
+    },
+    CasOk,
+    Error {
+        code: u32,
+        text: String,
+    },
+}
+
+/// Errors a Maelstrom kv service hands back, with the two documented error codes
+/// mapped to their own variants and everything else preserved as-is.
+#[derive(Debug)]
+pub enum KvError {
+    KeyDoesNotExist,
+    PreconditionFailed,
+    Rpc(RpcError),
+    Other { code: u32, text: String },
+}
+
+impl From<RpcError> for KvError {
+    fn from(err: RpcError) -> Self {
+        KvError::Rpc(err)
+    }
+}
+
+fn kv_error(code: u32, text: String) -> KvError {
+    match code {
+        ERROR_KEY_DOES_NOT_EXIST => KvError::KeyDoesNotExist,
+        ERROR_PRECONDITION_FAILED => KvError::PreconditionFailed,
+        _ => KvError::Other { code, text },
+    }
+}
+
+/// Thin client for one of Maelstrom's external key-value services, built on top of
+/// [`MsgHandler::rpc`]. Construct one with the service's node id (`"seq-kv"`,
+/// `"lin-kv"` or `"lww-kv"`) and call it from a node whose own `Msg` type is
+/// [`KvBody<T>`].
+pub struct KvClient {
+    service: String,
+}
+
+impl KvClient {
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+
+    pub async fn read<H, T>(&self, handler: &H, key: String) -> Result<T, KvError>
+    where
+        H: MsgHandler<KvBody<T>>,
+        T: Serialize + for<'de> Deserialize<'de> + Send + Clone + 'static,
+    {
+        let reply = handler.rpc(self.service.clone(), KvBody::Read { key }).await?;
+        match reply.body.msg {
+            KvBody::ReadOk { value } => Ok(value),
+            KvBody::Error { code, text } => Err(kv_error(code, text)),
+            _ => Err(KvError::Other {
+                code: 0,
+                text: "unexpected reply to read".to_string(),
+            }),
+        }
+    }
+
+    pub async fn write<H, T>(&self, handler: &H, key: String, value: T) -> Result<(), KvError>
+    where
+        H: MsgHandler<KvBody<T>>,
+        T: Serialize + for<'de> Deserialize<'de> + Send + Clone + 'static,
+    {
+        let reply = handler
+            .rpc(self.service.clone(), KvBody::Write { key, value })
+            .await?;
+        match reply.body.msg {
+            KvBody::WriteOk => Ok(()),
+            KvBody::Error { code, text } => Err(kv_error(code, text)),
+            _ => Err(KvError::Other {
+                code: 0,
+                text: "unexpected reply to write".to_string(),
+            }),
+        }
+    }
+
+    pub async fn compare_and_swap<H, T>(
+        &self,
+        handler: &H,
+        key: String,
+        from: T,
+        to: T,
+        create_if_missing: bool,
+    ) -> Result<(), KvError>
+    where
+        H: MsgHandler<KvBody<T>>,
+        T: Serialize + for<'de> Deserialize<'de> + Send + Clone + 'static,
+    {
+        let reply = handler
+            .rpc(
+                self.service.clone(),
+                KvBody::Cas {
+                    key,
+                    from,
+                    to,
+                    create_if_not_exists: create_if_missing.then_some(true),
+                },
+            )
+            .await?;
+        match reply.body.msg {
+            KvBody::CasOk => Ok(()),
+            KvBody::Error { code, text } => Err(kv_error(code, text)),
+            _ => Err(KvError::Other {
+                code: 0,
+                text: "unexpected reply to cas".to_string(),
+            }),
+        }
+    }
+}
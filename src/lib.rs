@@ -1,11 +1,45 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
 use tokio::{
     io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter, Stdout},
-    sync::{mpsc, RwLock},
-    time::sleep,
+    sync::{mpsc, oneshot, RwLock},
+    task::JoinSet,
+    time::{sleep, Instant},
 };
 
 use serde::{Deserialize, Serialize};
 
+pub mod crdt;
+pub mod kv;
+
+/// Errors that can arise from a [`MsgHandler::rpc`] call.
+#[derive(Debug)]
+pub enum RpcError {
+    /// No reply arrived within the configured timeout, even after retries.
+    Timeout,
+    /// The pending reply slot was dropped before a reply arrived.
+    ChannelClosed,
+}
+
+/// Controls how often `bg_task` fires.
+#[derive(Debug, Clone, Copy)]
+pub enum PacingPolicy {
+    /// Always wait exactly the given number of milliseconds between invocations.
+    Fixed(u64),
+    /// Shrink the interval toward `floor_ms` while [`MsgHandler::bg_task_backlog`]
+    /// stays above `target`, and grow it toward `ceil_ms` while the node is idle
+    /// (backlog `0`), so each tick aims to work through roughly `target` items.
+    Adaptive {
+        floor_ms: u64,
+        ceil_ms: u64,
+        target: usize,
+    },
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct InitBody {
     node_id: String,
@@ -52,35 +86,51 @@ where
     M: Send,
 {
     ReceivedMsg(Message<M>),
-    TriggerBgTask,
+    Shutdown,
 }
 
-pub struct PartialNode {
+pub struct PartialNode<Msg>
+where
+    Msg: Send + 'static,
+{
     pub id: String,
     pub peers: Vec<String>,
     pub msg_id: usize,
     pub output: RwLock<BufWriter<Stdout>>,
+    /// Replies awaited by an in-flight [`MsgHandler::rpc`] call, keyed by the `msg_id`
+    /// of the request that's still outstanding.
+    pub pending: RwLock<HashMap<usize, oneshot::Sender<Message<Msg>>>>,
 }
 
 pub trait MsgHandler<Msg>
 where
     Msg: Serialize + for<'de> Deserialize<'de> + Send + 'static,
+    // `reply`/`send_msg`/`rpc`/`gossip_crdt` all hold `&self` across an `.await`
+    // (and are themselves spawned via `tokio::spawn` from `run`), so the shared
+    // reference needs to be `Send`, which requires `Self: Sync`.
+    Self: Sync,
 {
     /// Create a new Message handling node from the data gathered from the init message.
     /// In the simplest case, this is just returning the same data in a new struct.
-    fn new(partial_node: PartialNode) -> Self
+    fn new(partial_node: PartialNode<Msg>) -> Self
     where
         Self: MsgHandler<Msg> + Sized;
 
-    /// The core message-handling logic of a node in the distributed system.
-    async fn handle_msg(&mut self, msg: Message<Msg>)
+    /// The core message-handling logic of a node in the distributed system. Runs
+    /// concurrently with every other in-flight `handle_msg`/`bg_task` invocation, so
+    /// any mutable state this touches must be behind its own lock or atomic.
+    ///
+    /// Spelled out as `-> impl Future<...> + Send` rather than `async fn` because
+    /// `run` spawns this future onto its own task: it must be `Send`, and an `async
+    /// fn` in a trait doesn't promise that on its own.
+    fn handle_msg(&self, msg: Message<Msg>) -> impl Future<Output = ()> + Send
     where
         Msg: Serialize + Send;
 
     /// Background task. A recurring event at a fixed interval that you can write
     /// arbitrary code in. Set the frequency using the 'bg_task_interval_ms' method.
-    async fn bg_task(&mut self) {
-        ()
+    fn bg_task(&self) -> impl Future<Output = ()> + Send {
+        async {}
     }
 
     /// How frequently the 'bg_task' method should be called. Leave as u64::MAX
@@ -89,16 +139,63 @@ where
         u64::MAX
     }
 
-    fn get_msg_id(&mut self) -> &mut usize;
+    /// How `bg_task` should be paced. Defaults to a fixed interval driven by
+    /// `bg_task_interval_ms`, so existing handlers are unaffected; override with
+    /// `PacingPolicy::Adaptive` to speed up under backlog and idle down otherwise.
+    fn pacing_policy(&self) -> PacingPolicy {
+        PacingPolicy::Fixed(self.bg_task_interval_ms())
+    }
+
+    /// Size of whatever backlog `bg_task` is working through (e.g. unconfirmed
+    /// gossip). Only consulted under `PacingPolicy::Adaptive`.
+    fn bg_task_backlog(&self) -> usize {
+        0
+    }
+
+    /// Atomic `msg_id` allocator: lets `send_msg`/`reply`/`rpc` hand out ids from
+    /// `&self`, so they can be called from concurrently-running `handle_msg` tasks.
+    fn get_msg_id(&self) -> &AtomicUsize;
+
+    /// Allocate the next outgoing `msg_id`.
+    fn next_msg_id(&self) -> usize {
+        self.get_msg_id().fetch_add(1, Ordering::SeqCst)
+    }
 
     fn get_output(&self) -> &RwLock<BufWriter<Stdout>>;
 
+    fn get_id(&self) -> &str;
+
+    fn get_pending(&self) -> &RwLock<HashMap<usize, oneshot::Sender<Message<Msg>>>>;
+
+    /// Timeout for a single attempt of an [`rpc`](MsgHandler::rpc) call, in milliseconds.
+    fn rpc_timeout_ms(&self) -> u64 {
+        1000
+    }
+
+    /// How many times an [`rpc`](MsgHandler::rpc) call resends the request after a
+    /// timed-out attempt before giving up.
+    fn rpc_max_retries(&self) -> u32 {
+        3
+    }
+
+    /// Called once, after stdin closes or a shutdown signal arrives, before the
+    /// final output flush. Use this to emit any final gossip or pending replies.
+    fn on_shutdown(&self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// How long to wait for in-flight `handle_msg` tasks to finish on shutdown
+    /// before giving up and flushing anyway.
+    fn shutdown_drain_timeout_ms(&self) -> u64 {
+        2000
+    }
+
     /// Initializes a node and runs tasks to both parse the input stream and regularly trigger
     /// background events using a rudimentary event handler.
     async fn run<M>()
     where
         M: Serialize + for<'de> Deserialize<'de>,
-        Self: Sized,
+        Self: Sized + Send + Sync + 'static,
     {
         let mut input = BufReader::new(stdin()).lines();
         let output = RwLock::new(BufWriter::new(stdout()));
@@ -112,18 +209,19 @@ where
                 peers: init_body.peers.clone(),
                 msg_id: 0,
                 output,
+                pending: RwLock::new(HashMap::new()),
             },
             _ => {
                 panic!("Should recieve Init message first!")
             }
         };
 
-        let mut node = Self::new(node);
+        let node = Arc::new(Self::new(node));
 
         node.reply(&init_msg, InitMessages::InitOk).await;
 
         let (tx, mut rx) = mpsc::channel(10);
-        let tx2 = tx.clone();
+        let tx3 = tx.clone();
 
         tokio::spawn(async move {
             loop {
@@ -137,68 +235,240 @@ where
                     Err(_) => {
                         panic!("Input IO error");
                     }
-                    Ok(None) => break,
+                    Ok(None) => {
+                        // stdin closed: tell the event loop to shut down cleanly
+                        // instead of letting it hang waiting for more lines.
+                        let _ = tx.send(Events::Shutdown).await;
+                        break;
+                    }
                 }
             }
         });
 
-        let interval = node.bg_task_interval_ms();
-
         tokio::spawn(async move {
-            loop {
-                sleep(tokio::time::Duration::from_millis(interval)).await;
-                tx2.send(Events::TriggerBgTask)
-                    .await
-                    .expect("Channel send error from sleep task");
+            let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("failed to install SIGTERM handler");
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = sigterm.recv() => {}
             }
+            let _ = tx3.send(Events::Shutdown).await;
         });
 
-        while let Some(event) = rx.recv().await {
-            match event {
-                Events::ReceivedMsg(message) => {
-                    node.handle_msg(message).await;
+        // `bg_delay_ms` is re-read from `pacing_policy`/`bg_task_backlog` after every
+        // invocation, rather than sampled once at startup, so adaptive handlers can
+        // speed up under backlog and idle down when there's nothing to do.
+        let mut bg_delay_ms = match node.pacing_policy() {
+            PacingPolicy::Fixed(ms) => ms,
+            PacingPolicy::Adaptive { ceil_ms, .. } => ceil_ms,
+        };
+        let mut avg_bg_task_ms: f64 = 0.0;
+        const EWMA_ALPHA: f64 = 0.2;
+
+        // Tracks every spawned `handle_msg` task so shutdown can wait for them
+        // to finish (up to `shutdown_drain_timeout_ms`) instead of dropping
+        // whatever output they hadn't written yet.
+        let mut handler_tasks: JoinSet<()> = JoinSet::new();
+
+        // Kept across loop iterations so incoming messages don't restart the timer;
+        // it is only re-armed after `bg_task` has run.
+        let bg_sleep = sleep(Duration::from_millis(bg_delay_ms));
+        tokio::pin!(bg_sleep);
+
+        loop {
+            tokio::select! {
+                event = rx.recv() => {
+                    let Some(event) = event else { break };
+                    match event {
+                        Events::ReceivedMsg(message) => {
+                            let awaited = match message.body.in_reply_to {
+                                Some(id) => node.get_pending().write().await.remove(&id),
+                                None => None,
+                            };
+                            match awaited {
+                                Some(tx) => {
+                                    // Whoever was awaiting this reply may have already
+                                    // timed out and stopped listening; that's fine,
+                                    // just drop it.
+                                    let _ = tx.send(message);
+                                }
+                                None => {
+                                    // Spawned so a slow handler (e.g. one awaiting an
+                                    // `rpc` reply) can't stall delivery of the very
+                                    // reply it's waiting on, or any other message.
+                                    let node = Arc::clone(&node);
+                                    handler_tasks.spawn(async move {
+                                        node.handle_msg(message).await;
+                                    });
+                                }
+                            }
+                        }
+                        Events::Shutdown => {
+                            node.on_shutdown().await;
+                            // Give in-flight handlers a chance to finish (and write
+                            // whatever they still owed us) before the final flush,
+                            // instead of dropping them mid-write.
+                            let _ = tokio::time::timeout(
+                                Duration::from_millis(node.shutdown_drain_timeout_ms()),
+                                async { while handler_tasks.join_next().await.is_some() {} },
+                            )
+                            .await;
+                            node.get_output().write().await.flush().await.unwrap();
+                            break;
+                        }
+                    }
                 }
-                Events::TriggerBgTask => {
+                _ = &mut bg_sleep => {
+                    let start = Instant::now();
                     node.bg_task().await;
+                    let elapsed_ms = start.elapsed().as_millis() as f64;
+                    avg_bg_task_ms = EWMA_ALPHA * elapsed_ms + (1.0 - EWMA_ALPHA) * avg_bg_task_ms;
+
+                    bg_delay_ms = match node.pacing_policy() {
+                        PacingPolicy::Fixed(ms) => ms,
+                        PacingPolicy::Adaptive { floor_ms, ceil_ms, target } => {
+                            let backlog = node.bg_task_backlog();
+                            let next_ms = if backlog > target {
+                                bg_delay_ms / 2
+                            } else if backlog == 0 {
+                                bg_delay_ms.saturating_mul(2)
+                            } else {
+                                bg_delay_ms
+                            };
+                            next_ms.max(avg_bg_task_ms.round() as u64).clamp(floor_ms, ceil_ms)
+                        }
+                    };
+                    bg_sleep.as_mut().set(sleep(Duration::from_millis(bg_delay_ms)));
                 }
             }
         }
     }
 
-    async fn reply<M>(&mut self, msg: &Message<M>, rsp: M)
+    fn reply<M>(&self, msg: &Message<M>, rsp: M) -> impl Future<Output = ()> + Send
     where
-        M: Serialize,
+        M: Serialize + Send + Sync,
     {
-        let rsp_body = Body {
-            msg_id: Some(*self.get_msg_id()),
-            in_reply_to: msg.body.msg_id,
-            msg: rsp,
-        };
-        self.send_msg(msg.response(rsp_body)).await;
+        async move {
+            let rsp_body = Body {
+                msg_id: Some(self.next_msg_id()),
+                in_reply_to: msg.body.msg_id,
+                msg: rsp,
+            };
+            self.send_msg(msg.response(rsp_body)).await;
+        }
     }
 
-    async fn send_msg<M>(&mut self, msg: Message<M>)
+    fn send_msg<M>(&self, msg: Message<M>) -> impl Future<Output = ()> + Send
     where
-        M: Serialize,
+        M: Serialize + Send,
     {
-        *self.get_msg_id() += 1;
-        let output = self.get_output();
-        self.send_msg_inner(output, msg).await
+        async move {
+            let output = self.get_output();
+            self.send_msg_inner(output, msg).await
+        }
     }
 
-    /// Allows you to send messages without having to mutably borrow self. Only use as break glass option
-    /// CAUTION: doesn't incr the msg_id - you must do that manually if you use this.
-    async fn send_msg_inner<M>(&self, output: &RwLock<BufWriter<Stdout>>, msg: Message<M>)
+    /// Like `send_msg`, but takes the output lock directly. Useful when the caller
+    /// already holds a reference to it alongside other `&self` borrows. `msg_id`
+    /// allocation is always the caller's job (see `next_msg_id`) now that ids come
+    /// from a shared atomic rather than a field `send_msg` could bump for you.
+    ///
+    /// Holds a single write guard across the whole line (body + newline + flush)
+    /// rather than re-acquiring it per write: with `handle_msg` now spawned per
+    /// message, concurrent senders re-acquiring the lock between writes could
+    /// interleave their bytes onto the same stdout line.
+    fn send_msg_inner<M>(
+        &self,
+        output: &RwLock<BufWriter<Stdout>>,
+        msg: Message<M>,
+    ) -> impl Future<Output = ()> + Send
     where
-        M: Serialize,
+        M: Serialize + Send,
     {
-        output
-            .write()
-            .await
-            .write_all(serde_json::to_string(&msg).unwrap().as_bytes())
-            .await
-            .unwrap();
-        output.write().await.write_all(b"\n").await.unwrap();
-        output.write().await.flush().await.unwrap();
+        async move {
+            let line = serde_json::to_string(&msg).unwrap();
+            let mut output = output.write().await;
+            output.write_all(line.as_bytes()).await.unwrap();
+            output.write_all(b"\n").await.unwrap();
+            output.flush().await.unwrap();
+        }
+    }
+
+    /// Send `body` to `dest` and await the matching reply (matched on `in_reply_to`),
+    /// retrying with a fresh `msg_id` up to [`rpc_max_retries`](MsgHandler::rpc_max_retries)
+    /// times if [`rpc_timeout_ms`](MsgHandler::rpc_timeout_ms) elapses with no reply.
+    fn rpc(
+        &self,
+        dest: String,
+        body: Msg,
+    ) -> impl Future<Output = Result<Message<Msg>, RpcError>> + Send
+    where
+        Msg: Clone,
+    {
+        async move {
+            let mut last_err = RpcError::Timeout;
+
+            for _ in 0..=self.rpc_max_retries() {
+                let msg_id = self.next_msg_id();
+
+                let (tx, rx) = oneshot::channel();
+                self.get_pending().write().await.insert(msg_id, tx);
+
+                let msg = Message {
+                    src: self.get_id().to_string(),
+                    dst: dest.clone(),
+                    body: Body {
+                        msg_id: Some(msg_id),
+                        in_reply_to: None,
+                        msg: body.clone(),
+                    },
+                };
+                let output = self.get_output();
+                self.send_msg_inner(output, msg).await;
+
+                match tokio::time::timeout(Duration::from_millis(self.rpc_timeout_ms()), rx).await
+                {
+                    Ok(Ok(reply)) => return Ok(reply),
+                    Ok(Err(_)) => last_err = RpcError::ChannelClosed,
+                    Err(_) => {
+                        self.get_pending().write().await.remove(&msg_id);
+                        last_err = RpcError::Timeout;
+                    }
+                }
+            }
+
+            Err(last_err)
+        }
+    }
+
+    /// Ships `state` to every peer in `peers`, wrapped with `to_msg`. Call this from
+    /// `bg_task` to get full CRDT gossip convergence: merge whatever arrives back in
+    /// `handle_msg` with [`crdt::Crdt::merge`] and the grow-only-counter/CRDT workloads
+    /// become a state field plus this one call instead of a bespoke status machine.
+    fn gossip_crdt<M, C>(
+        &self,
+        self_id: &str,
+        peers: &[String],
+        state: &C,
+        to_msg: impl Fn(C) -> M + Send,
+    ) -> impl Future<Output = ()> + Send
+    where
+        M: Serialize + Send,
+        C: crdt::Crdt + Clone + Sync,
+    {
+        async move {
+            for peer in peers {
+                let msg = Message {
+                    src: self_id.to_string(),
+                    dst: peer.clone(),
+                    body: Body {
+                        msg_id: Some(self.next_msg_id()),
+                        in_reply_to: None,
+                        msg: to_msg(state.clone()),
+                    },
+                };
+                self.send_msg(msg).await;
+            }
+        }
     }
 }